@@ -0,0 +1,106 @@
+/*
+------------------------------------
+              Task
+------------------------------------
+The unit of work scheduled by the `TaskHandler`. A task carries its
+metadata (`TaskMeta`) describing how it should be scheduled — its
+priority, the concurrency class it competes in, and what should happen
+when it finishes or dies — and produces a future each time it is run.
+
+`execute` takes `&self` so a task can be re-run by the supervisor under
+its restart policy; the future it returns must be `'static`, so tasks
+clone whatever state they need into it.
+
+*/
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// The future produced by a [`Task`]. `'static` so it can be spawned and
+/// re-spawned independently of the task that created it.
+pub type TaskFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Scheduling priority. Higher-priority tasks are popped first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+    Critical,
+}
+
+/// Concurrency class. The handler caps how many tasks of each class may
+/// run at once, so e.g. a flood of one-shot captures cannot starve the
+/// long-lived pollers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConcurrencyClass {
+    /// Lifetime-spanning pollers and watchers.
+    Poller,
+    /// Capture work that is bounded in how much may run concurrently.
+    Capture,
+    /// Fire-and-forget tasks with no meaningful restart.
+    OneShot,
+}
+
+/// What the supervisor does when a task's handle completes.
+#[derive(Debug, Clone, Copy)]
+pub enum RestartPolicy {
+    /// Never restart; the task runs exactly once.
+    Never,
+    /// Restart only if the task panicked. A task stopped through its
+    /// cancellation token is not restarted.
+    OnPanic,
+    /// Always restart, backing off exponentially between attempts.
+    Always {
+        base_backoff: Duration,
+        max_backoff: Duration,
+    },
+}
+
+/// Metadata describing how a task should be scheduled and supervised.
+#[derive(Debug, Clone)]
+pub struct TaskMeta {
+    pub name: String,
+    pub priority: Priority,
+    pub class: ConcurrencyClass,
+    pub restart: RestartPolicy,
+}
+
+impl TaskMeta {
+    /// A normal-priority, never-restarting one-shot task. Use the
+    /// builder-style setters below to change the defaults.
+    pub fn new(name: impl Into<String>) -> TaskMeta {
+        TaskMeta {
+            name: name.into(),
+            priority: Priority::Normal,
+            class: ConcurrencyClass::OneShot,
+            restart: RestartPolicy::Never,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> TaskMeta {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_class(mut self, class: ConcurrencyClass) -> TaskMeta {
+        self.class = class;
+        self
+    }
+
+    pub fn with_restart(mut self, restart: RestartPolicy) -> TaskMeta {
+        self.restart = restart;
+        self
+    }
+}
+
+/// A schedulable unit of work.
+pub trait Task {
+    /// Scheduling metadata for this task.
+    fn data(&self) -> &TaskMeta;
+
+    /// Produce the future that does the work. May be called more than
+    /// once if the task's restart policy requeues it.
+    fn execute(&self) -> TaskFuture;
+}
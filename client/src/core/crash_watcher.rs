@@ -0,0 +1,291 @@
+/*
+------------------------------------
+            Crash Watcher
+------------------------------------
+Watches a single monitored game process and, when that process faults,
+writes a Windows minidump together with a JSON metadata sidecar so the
+crash can be diagnosed after the fact.
+
+The watcher attaches to the game as a debugger and blocks on the debug
+event stream — which costs nothing while the game is healthy — so it
+must run on a blocking thread rather than the supervisor's
+current-thread runtime. Capturing on the first-chance exception means
+the dump is taken while the process is still alive and its memory is
+intact, rather than post-mortem when there is nothing left to dump. It
+is scheduled like any other long-lived task through
+`TaskHandler::add_task`.
+
+*/
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+use uuid::Uuid;
+use windows::core::PCWSTR;
+use windows::{
+    Win32::Foundation::*,
+    Win32::Storage::FileSystem::*,
+    Win32::System::Diagnostics::Debug::*,
+    Win32::System::Threading::*,
+    Win32::UI::WindowsAndMessaging::*,
+};
+
+use client_backend::utils::{get_file_path_hwnd, hwnd_to_string};
+
+use crate::base::task::{ConcurrencyClass, Task, TaskFuture, TaskMeta};
+
+/// Directory under which per-crash folders are created. Each crash gets
+/// its own UUID-named sub-directory holding the `.dmp` and `.extra`.
+const CRASH_ROOT: &str = "crashes";
+
+/// Metadata serialized next to every minidump as a `.extra` file.
+#[derive(Serialize)]
+struct CrashRecord {
+    pid: u32,
+    image_path: Option<String>,
+    window_title: Option<String>,
+    /// NTSTATUS exception code that triggered the capture.
+    exception_code: u32,
+    /// Unix timestamp (seconds) at which the crash was observed.
+    timestamp: u64,
+}
+
+/// Long-lived task that debugs a monitored process and captures a
+/// minidump on the first-chance crash exception.
+pub struct CrashWatcher {
+    meta: TaskMeta,
+    hwnd: HWND,
+    pid: u32,
+    dump_type: MINIDUMP_TYPE,
+}
+
+impl CrashWatcher {
+    /// Build a watcher for the process that owns `hwnd`. Returns `None`
+    /// if the window no longer has an owning process.
+    pub fn new(hwnd: HWND) -> Option<CrashWatcher> {
+        let mut pid = 0u32;
+        unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+        if pid == 0 {
+            return None;
+        }
+
+        Some(CrashWatcher {
+            meta: TaskMeta::new(format!("crash_watcher:{pid}"))
+                .with_class(ConcurrencyClass::Poller),
+            hwnd,
+            pid,
+            dump_type: MiniDumpWithFullMemoryInfo | MiniDumpWithDataSegs,
+        })
+    }
+
+    /// Override the default dump type (`MiniDumpWithFullMemoryInfo |
+    /// MiniDumpWithDataSegs`).
+    pub fn with_dump_type(mut self, dump_type: MINIDUMP_TYPE) -> CrashWatcher {
+        self.dump_type = dump_type;
+        self
+    }
+
+    async fn watch(pid: u32, hwnd: HWND, dump_type: MINIDUMP_TYPE) {
+        // Resolve the window details now, on the async side: `HWND` is not
+        // `Send` and must not cross onto the blocking debugger thread.
+        let image_path = get_file_path_hwnd(hwnd);
+        let window_title = hwnd_to_string(hwnd);
+
+        // The debug loop blocks for the whole lifetime of the game, so run
+        // it on a blocking thread rather than stalling the current-thread
+        // supervisor runtime and every other local task.
+        let result = tokio::task::spawn_blocking(move || {
+            run_debugger(pid, dump_type, image_path, window_title)
+        })
+        .await;
+
+        if let Ok(Err(e)) = result {
+            log::error!("crash watcher for pid {pid} failed: {e}");
+        }
+    }
+}
+
+/// Attach to `pid` as a debugger and pump its debug-event stream until it
+/// faults or exits. On the first-chance crash exception the process is
+/// frozen with its memory intact, so that is where the dump is taken.
+fn run_debugger(
+    pid: u32,
+    dump_type: MINIDUMP_TYPE,
+    image_path: Option<String>,
+    window_title: Option<String>,
+) -> windows::core::Result<()> {
+    unsafe { DebugActiveProcess(pid)? };
+    // Detaching must not take the game down with us.
+    let _ = unsafe { DebugSetProcessKillOnExit(false) };
+
+    let mut captured = false;
+    loop {
+        let mut event = DEBUG_EVENT::default();
+        if unsafe { WaitForDebugEvent(&mut event, INFINITE) }.is_err() {
+            break;
+        }
+
+        // Exceptions pass through to the app's own handler by default.
+        let mut status = DBG_EXCEPTION_NOT_HANDLED;
+
+        if event.dwDebugEventCode == EXCEPTION_DEBUG_EVENT {
+            let info = unsafe { event.u.Exception };
+            let code = info.ExceptionRecord.ExceptionCode.0 as u32;
+            if !captured && info.dwFirstChance != 0 && is_crash_exception(code) {
+                log::info!("pid {pid} faulted (0x{code:08x}); capturing dump");
+                if let Err(e) = capture(
+                    pid,
+                    dump_type,
+                    event.dwThreadId,
+                    &info,
+                    &image_path,
+                    &window_title,
+                ) {
+                    log::error!("failed to capture crash dump for pid {pid}: {e}");
+                }
+                captured = true;
+            }
+        } else if event.dwDebugEventCode == EXIT_PROCESS_DEBUG_EVENT {
+            let _ = unsafe {
+                ContinueDebugEvent(event.dwProcessId, event.dwThreadId, DBG_CONTINUE)
+            };
+            break;
+        } else {
+            status = DBG_CONTINUE;
+        }
+
+        let _ = unsafe { ContinueDebugEvent(event.dwProcessId, event.dwThreadId, status) };
+        if captured {
+            break;
+        }
+    }
+
+    let _ = unsafe { DebugActiveProcessStop(pid) };
+    Ok(())
+}
+
+/// Write the minidump for the faulting process, anchored at the exception
+/// so the dump records the faulting thread and instruction.
+fn capture(
+    pid: u32,
+    dump_type: MINIDUMP_TYPE,
+    thread_id: u32,
+    info: &EXCEPTION_DEBUG_INFO,
+    image_path: &Option<String>,
+    window_title: &Option<String>,
+) -> windows::core::Result<()> {
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid)? };
+
+    let dir = PathBuf::from(CRASH_ROOT).join(Uuid::new_v4().to_string());
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| windows::core::Error::new(E_FAIL, format!("{e}")))?;
+
+    let dump_path = dir.join("crash.dmp");
+    let wide: Vec<u16> = dump_path
+        .to_string_lossy()
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+
+    let file = unsafe {
+        CreateFileW(
+            PCWSTR(wide.as_ptr()),
+            GENERIC_WRITE.0,
+            FILE_SHARE_MODE(0),
+            None,
+            CREATE_ALWAYS,
+            FILE_ATTRIBUTE_NORMAL,
+            None,
+        )?
+    };
+
+    // Grab the faulting thread's register context — the most load-bearing
+    // part of the dump. If the thread can't be opened we still write a
+    // dump, just without the context stream.
+    let mut context = CONTEXT {
+        ContextFlags: CONTEXT_FULL.0,
+        ..Default::default()
+    };
+    let context_ptr = match unsafe { OpenThread(THREAD_GET_CONTEXT, false, thread_id) } {
+        Ok(thread) => {
+            let ok = unsafe { GetThreadContext(thread, &mut context) }.is_ok();
+            let _ = unsafe { CloseHandle(thread) };
+            if ok {
+                &mut context as *mut CONTEXT
+            } else {
+                std::ptr::null_mut()
+            }
+        }
+        Err(e) => {
+            log::warn!("could not open faulting thread {thread_id}: {e}");
+            std::ptr::null_mut()
+        }
+    };
+
+    let mut record = info.ExceptionRecord;
+    let mut pointers = EXCEPTION_POINTERS {
+        ExceptionRecord: &mut record,
+        ContextRecord: context_ptr,
+    };
+    let mut exception = MINIDUMP_EXCEPTION_INFORMATION {
+        ThreadId: thread_id,
+        ExceptionPointers: &mut pointers,
+        ClientPointers: BOOL::from(false),
+    };
+
+    let result = unsafe {
+        MiniDumpWriteDump(
+            handle,
+            pid,
+            file,
+            dump_type,
+            Some(&exception as *const _),
+            None,
+            None,
+        )
+    };
+    let _ = unsafe { CloseHandle(file) };
+    let _ = unsafe { CloseHandle(handle) };
+    result?;
+
+    let record = CrashRecord {
+        pid,
+        image_path: image_path.clone(),
+        window_title: window_title.clone(),
+        exception_code: info.ExceptionRecord.ExceptionCode.0 as u32,
+        timestamp: unix_now(),
+    };
+    let extra = dir.join("crash.extra");
+    std::fs::write(&extra, serde_json::to_vec_pretty(&record).unwrap_or_default())
+        .map_err(|e| windows::core::Error::new(E_FAIL, format!("{e}")))?;
+
+    log::info!("wrote crash dump to {}", dir.display());
+    Ok(())
+}
+
+impl Task for CrashWatcher {
+    fn data(&self) -> &TaskMeta {
+        &self.meta
+    }
+
+    fn execute(&self) -> TaskFuture {
+        Box::pin(Self::watch(self.pid, self.hwnd, self.dump_type))
+    }
+}
+
+/// An exception is treated as a crash when the top nibble of its code is
+/// `0xC`: NTSTATUS "error" severity with the customer bit clear, which
+/// covers access violations, stack overflows, illegal instructions and the
+/// like. Customer-defined codes such as C++ exceptions (`0xE06D7363`) are
+/// not treated as crashes.
+fn is_crash_exception(code: u32) -> bool {
+    (code & 0xF000_0000) == 0xC000_0000
+}
+
+fn unix_now() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,2 @@
+pub mod crash_watcher;
+pub mod task_handler;
@@ -1,78 +1,438 @@
 /*
 ------------------------------------
-            Task Handler 
+            Task Handler
 ------------------------------------
-Handles the executing of all tasks in the system, this 
+Handles the executing of all tasks in the system, this
 includes tasks that will run for the lifetime of the application
 for instances such as polling events.
 
+Rather than draining a queue and spawning everything immediately, the
+handler is a small supervisor: it pops tasks in priority order, caps how
+many tasks of each concurrency class may run at once, and — when a task's
+handle finishes — inspects whether it completed normally, panicked, or
+was cancelled, and requeues it according to its `RestartPolicy` with an
+exponential backoff. Each running task carries a `CancellationToken` so
+individual long-lived tasks can be asked to stop.
+
 */
 
-use std::{collections::VecDeque, time::Duration};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::{spawn_local, JoinHandle};
+use tokio::time::{self, Instant};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use windows::Win32::Foundation::HWND;
+
+use client_backend::foreground::{self, ForegroundEvent};
+use client_backend::utils::{capture_identity, is_game, is_same_process, ProcessIdentity};
 
-use tokio::{task::{spawn_local, JoinHandle}, time};
+use crate::base::task::{ConcurrencyClass, Priority, RestartPolicy, Task, TaskMeta};
+
+/// How many tasks of each concurrency class may run simultaneously.
+fn class_cap(class: ConcurrencyClass) -> usize {
+    match class {
+        ConcurrencyClass::Poller => 16,
+        ConcurrencyClass::Capture => 4,
+        ConcurrencyClass::OneShot => 32,
+    }
+}
 
-use crate::base::task::{Task, TaskMeta};
+/// Default grace period to wait, once the last game window has closed,
+/// before shutting the handler down.
+const DEFAULT_GRACE: Duration = Duration::from_secs(5);
 
 pub struct TaskHandler {
-   task_queue: VecDeque<Box<dyn Task>>,
-   handles: Vec<TaskHandle>,
+    task_queue: VecDeque<Box<dyn Task>>,
+    handles: Vec<TaskHandle>,
+    /// Tasks waiting out a backoff before being re-queued.
+    backoff: Vec<Backoff>,
+    running_per_class: HashMap<ConcurrencyClass, usize>,
+    /// The live game windows we are monitoring, keyed by PID and pinned
+    /// to a specific process instance via its stable identity.
+    live_games: HashMap<u32, (HWND, ProcessIdentity)>,
+    /// When the live set last became empty, for grace-period tracking.
+    empty_since: Option<Instant>,
+    /// Whether a game has been tracked at all this run, so a cold start
+    /// with no game in the foreground is not mistaken for a last-close.
+    has_ever_tracked: bool,
+    /// Whether to exit once the last game window closes, or stay resident
+    /// and keep polling for a new game.
+    exit_on_last_close: bool,
+    grace: Duration,
+    /// Broadcast channel tasks can watch to learn the handler is shutting
+    /// down. `true` means "shut down".
+    shutdown_ch: (watch::Sender<bool>, watch::Receiver<bool>),
+    /// Optional final-cleanup hook run before the loop exits.
+    on_last_window_closed: Option<Box<dyn FnOnce()>>,
 }
 
 struct TaskHandle {
     task_meta: TaskMeta,
-    handle: JoinHandle<()> 
+    /// Kept alive so the task can be re-run under its restart policy.
+    task: Box<dyn Task>,
+    token: CancellationToken,
+    handle: JoinHandle<()>,
+    /// Set to true once the task's future runs to completion, so a
+    /// finished-but-not-completed handle can be recognised as a panic.
+    done: Arc<AtomicBool>,
+    /// Number of times this task has already been (re)started.
+    attempt: u32,
+}
+
+/// A task parked until `ready_at`, carrying the attempt count so the
+/// backoff keeps growing across restarts.
+struct Backoff {
+    task: Box<dyn Task>,
+    attempt: u32,
+    ready_at: Instant,
 }
 
-impl TaskHandle{
-    fn create(t: Box<dyn Task>) -> TaskHandle {
+impl TaskHandle {
+    fn create(t: Box<dyn Task>, attempt: u32) -> TaskHandle {
         let meta = t.data().clone();
         log::info!("creating task handle for task: {}", meta.name);
-        let task_handle = spawn_local(t.execute());
 
-        TaskHandle{
+        let token = CancellationToken::new();
+        let fut = t.execute();
+        let child = token.clone();
+        let done = Arc::new(AtomicBool::new(false));
+        let done_flag = done.clone();
+        // Wrap the future so a cancel request short-circuits it, and flag
+        // normal completion so a panic (which unwinds past this line) is
+        // distinguishable later.
+        let handle = spawn_local(async move {
+            tokio::select! {
+                _ = child.cancelled() => {}
+                _ = fut => { done_flag.store(true, Ordering::SeqCst); }
+            }
+        });
+
+        TaskHandle {
             task_meta: meta,
-            handle: task_handle 
-        }   
+            task: t,
+            token,
+            handle,
+            done,
+            attempt,
+        }
     }
 }
 
 impl TaskHandler {
     pub fn new() -> TaskHandler {
-        TaskHandler { task_queue: VecDeque::new(), handles: Vec::new() }
+        TaskHandler {
+            task_queue: VecDeque::new(),
+            handles: Vec::new(),
+            backoff: Vec::new(),
+            running_per_class: HashMap::new(),
+            live_games: HashMap::new(),
+            empty_since: None,
+            has_ever_tracked: false,
+            exit_on_last_close: true,
+            grace: DEFAULT_GRACE,
+            shutdown_ch: watch::channel(false),
+            on_last_window_closed: None,
+        }
+    }
+
+    /// Stay resident and keep polling for a new game after the last game
+    /// window closes, rather than exiting. The default is to exit.
+    pub fn stay_resident(mut self) -> TaskHandler {
+        self.exit_on_last_close = false;
+        self
+    }
+
+    /// Override how long the live-game set must stay empty before the
+    /// handler shuts down.
+    pub fn with_grace_period(mut self, grace: Duration) -> TaskHandler {
+        self.grace = grace;
+        self
+    }
+
+    /// Register a hook run once, just before the loop exits because the
+    /// last game window closed. Use it to flush capture buffers or write
+    /// crash sidecars.
+    pub fn on_last_window_closed(mut self, hook: impl FnOnce() + 'static) -> TaskHandler {
+        self.on_last_window_closed = Some(Box::new(hook));
+        self
+    }
+
+    /// A receiver tasks can watch to learn when shutdown begins.
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown_ch.1.clone()
     }
 
     pub fn add_task(&mut self, task: Box<dyn Task>) {
         log::info!("adding task to task queue: {}", task.data().name);
-        self.task_queue.push_back(task); 
+        self.task_queue.push_back(task);
+    }
+
+    /// Spawn as many queued tasks as the per-class caps allow, highest
+    /// priority first.
+    fn run_tasks(&mut self) {
+        while let Some(idx) = self.next_runnable() {
+            let t = self.task_queue.remove(idx).expect("index from next_runnable");
+            self.spawn(t, 0);
+        }
     }
 
-    fn run_tasks(&mut self){
-        while let Some(t) = self.task_queue.pop_front() {
-            let t_handle = TaskHandle::create(t);
-            log::info!("Starting task: {}", t_handle.task_meta.name);
-            self.handles.push(t_handle);
+    /// Spawn a single task at the given attempt count and account for it.
+    fn spawn(&mut self, t: Box<dyn Task>, attempt: u32) {
+        let class = t.data().class;
+        let t_handle = TaskHandle::create(t, attempt);
+        log::info!("Starting task: {}", t_handle.task_meta.name);
+        *self.running_per_class.entry(class).or_insert(0) += 1;
+        self.handles.push(t_handle);
+    }
+
+    /// Index of the highest-priority queued task whose class still has
+    /// spare capacity, or `None` if nothing can start right now.
+    fn next_runnable(&self) -> Option<usize> {
+        let mut best: Option<(usize, Priority)> = None;
+        for (i, t) in self.task_queue.iter().enumerate() {
+            let class = t.data().class;
+            let running = self.running_per_class.get(&class).copied().unwrap_or(0);
+            if running >= class_cap(class) {
+                continue;
+            }
+            let priority = t.data().priority;
+            if best.map_or(true, |(_, p)| priority > p) {
+                best = Some((i, priority));
+            }
         }
+        best.map(|(i, _)| i)
     }
 
-    fn clean_handles(&mut self){
-        // clean handles by removing finished tasks
-        self.handles.retain(|handle| {
-            if handle.handle.is_finished() {
-                log::info!("Task completed: {}", handle.task_meta.name);
-                false // remove handle
+    /// Respawn any backoff entries whose timer has elapsed, provided
+    /// their class has spare capacity; otherwise leave them parked.
+    fn drain_backoff(&mut self) {
+        let now = Instant::now();
+        let mut i = 0;
+        while i < self.backoff.len() {
+            let ready = self.backoff[i].ready_at <= now;
+            let class = self.backoff[i].task.data().class;
+            let has_room =
+                self.running_per_class.get(&class).copied().unwrap_or(0) < class_cap(class);
+            if ready && has_room {
+                let b = self.backoff.remove(i);
+                log::info!("re-queuing task after backoff: {}", b.task.data().name);
+                self.spawn(b.task, b.attempt);
             } else {
-                true // keep handle
+                i += 1;
             }
+        }
+    }
+
+    /// Inspect finished handles and apply each task's restart policy.
+    fn clean_handles(&mut self) {
+        let mut still_running = Vec::with_capacity(self.handles.len());
+        for h in std::mem::take(&mut self.handles) {
+            if !h.handle.is_finished() {
+                still_running.push(h);
+                continue;
+            }
+
+            let class = h.task_meta.class;
+            if let Some(n) = self.running_per_class.get_mut(&class) {
+                *n = n.saturating_sub(1);
+            }
+
+            // A finished handle either completed, panicked, or was
+            // cancelled. The wrapper sets `done` on normal completion and
+            // the token records cancellation; a finished handle that is
+            // neither must have unwound via panic.
+            let outcome = if h.token.is_cancelled() {
+                Outcome::Cancelled
+            } else if h.done.load(Ordering::SeqCst) {
+                Outcome::Completed
+            } else {
+                Outcome::Panicked
+            };
+            log::info!("Task finished ({outcome:?}): {}", h.task_meta.name);
+            self.apply_restart(h, outcome);
+        }
+        self.handles = still_running;
+    }
+
+    /// Requeue `h`'s task onto the backoff list if its policy calls for a
+    /// restart given `outcome`.
+    fn apply_restart(&mut self, h: TaskHandle, outcome: Outcome) {
+        // An explicit cancel (via `cancel`/`shutdown`) always wins over
+        // the restart policy — otherwise `cancel(name)` could never stop
+        // the very `Always`/`OnPanic` pollers it exists to stop.
+        if outcome == Outcome::Cancelled {
+            return;
+        }
+
+        let restart = match h.task_meta.restart {
+            RestartPolicy::Never => return,
+            RestartPolicy::OnPanic if outcome == Outcome::Completed => return,
+            policy => policy,
+        };
+
+        let attempt = h.attempt + 1;
+        let delay = backoff_delay(&restart, attempt);
+        log::info!(
+            "restarting {} (attempt {attempt}) after {:?}",
+            h.task_meta.name,
+            delay
+        );
+        self.backoff.push(Backoff {
+            task: h.task,
+            attempt,
+            ready_at: Instant::now() + delay,
         });
+    }
+
+    /// Cancel a single running task by name, if present.
+    pub fn cancel(&mut self, name: &str) {
+        for h in &self.handles {
+            if h.task_meta.name == name {
+                log::info!("cancelling task: {name}");
+                h.token.cancel();
+            }
+        }
+        self.task_queue.retain(|t| t.data().name != name);
+        self.backoff.retain(|b| b.task.data().name != name);
+    }
+
+    /// Cancel every outstanding task and await their completion.
+    pub async fn shutdown(&mut self) {
+        log::info!("shutting down task handler");
+        for h in &self.handles {
+            h.token.cancel();
+        }
+        for h in std::mem::take(&mut self.handles) {
+            let _ = h.handle.await;
+        }
+        self.task_queue.clear();
+        self.backoff.clear();
+        self.running_per_class.clear();
+    }
+
+    /// Start tracking the game owning `hwnd`, if it is one. Records the
+    /// stable process identity so a later PID reuse is not mistaken for
+    /// the same game still running.
+    fn track_if_game(&mut self, hwnd: HWND) {
+        if is_game(Some(hwnd)).is_none() {
+            return;
+        }
+        if let Some(identity) = capture_identity(hwnd) {
+            if self.live_games.insert(identity.pid, (hwnd, identity)).is_none() {
+                log::info!("tracking game window {hwnd:?} (pid {})", identity.pid);
+            }
+            self.has_ever_tracked = true;
+            self.empty_since = None;
+        }
+    }
+
+    /// Drop any tracked game whose specific process instance has ended
+    /// (dead window, or a recycled PID now owned by another process).
+    fn prune_dead_games(&mut self) {
+        self.live_games
+            .retain(|_, (hwnd, identity)| is_same_process(*identity, *hwnd));
 
+        // Only arm the grace timer once at least one game has actually
+        // been tracked, so a cold start with nothing in the foreground is
+        // not treated as a last-close.
+        if self.live_games.is_empty() && self.has_ever_tracked {
+            self.empty_since.get_or_insert_with(Instant::now);
+        } else {
+            self.empty_since = None;
+        }
+    }
+
+    /// True once a game has been tracked and the live-game set has then
+    /// stayed empty for the grace period, with exit-on-last-close enabled.
+    fn should_shutdown(&self) -> bool {
+        self.exit_on_last_close
+            && self.has_ever_tracked
+            && self
+                .empty_since
+                .is_some_and(|since| since.elapsed() >= self.grace)
     }
 
     pub async fn start(mut self) {
+        // Event-driven foreground hook: we wake on focus changes rather
+        // than polling (see the `foreground` module).
+        let (_hook, mut events) = foreground::install();
+
+        // Seed tracking from whatever is in the foreground right now.
+        if let Some(hwnd) = client_backend::utils::get_foreground_window_hwnd() {
+            self.track_if_game(hwnd);
+        }
+        self.run_tasks();
+
         loop {
-            self.run_tasks();
+            // Wake on the next backoff deadline, a focus change, or a
+            // short supervisory tick to reap handles and re-check games.
+            let supervise = Instant::now() + Duration::from_millis(250);
+            let deadline = self
+                .backoff
+                .iter()
+                .map(|b| b.ready_at)
+                .min()
+                .map_or(supervise, |ready| ready.min(supervise));
+            let tick = time::sleep_until(deadline);
+
+            tokio::select! {
+                event = events.next() => {
+                    match event {
+                        Some(ForegroundEvent::Focused(hwnd)) => {
+                            log::debug!("foreground changed to {hwnd:?}");
+                            self.track_if_game(hwnd);
+                        }
+                        Some(ForegroundEvent::Destroyed(hwnd)) => {
+                            log::debug!("window destroyed {hwnd:?}");
+                        }
+                        None => break,
+                    }
+                }
+                _ = tick => {}
+            }
+
+            self.prune_dead_games();
+            self.drain_backoff();
             self.clean_handles();
-            time::sleep(Duration::from_millis(50)).await;
+            self.run_tasks();
+
+            if self.should_shutdown() {
+                log::info!("all game windows closed; shutting down");
+                if let Some(hook) = self.on_last_window_closed.take() {
+                    hook();
+                }
+                let _ = self.shutdown_ch.0.send(true);
+                self.shutdown().await;
+                break;
+            }
+        }
+    }
+}
+
+/// Outcome of a finished task handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Completed,
+    Panicked,
+    Cancelled,
+}
+
+/// Exponential backoff for the given attempt under an `Always` policy.
+/// Non-`Always` policies restart immediately.
+fn backoff_delay(policy: &RestartPolicy, attempt: u32) -> Duration {
+    match policy {
+        RestartPolicy::Always {
+            base_backoff,
+            max_backoff,
+        } => {
+            let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+            base_backoff.saturating_mul(factor).min(*max_backoff)
         }
+        _ => Duration::ZERO,
     }
 }
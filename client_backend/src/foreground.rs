@@ -0,0 +1,160 @@
+/*
+Event-driven foreground-window tracking.
+
+Instead of polling `GetForegroundWindow` every 50 ms we install a
+`SetWinEventHook` on a dedicated thread that pumps a `GetMessage` loop.
+The hooks are out-of-context, so the WinEvent callback runs on that hook
+thread inside our own process; it does no work beyond marshalling the
+HWND back onto a
+`tokio::sync::mpsc` channel; the async runtime then reacts to focus
+changes the instant they happen and idle CPU drops to near zero.
+*/
+
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use windows::Win32::Foundation::*;
+use windows::Win32::UI::Accessibility::*;
+use windows::Win32::UI::WindowsAndMessaging::*;
+
+/// A foreground-tracking change observed by the hook.
+#[derive(Debug, Clone, Copy)]
+pub enum ForegroundEvent {
+    /// The foreground window changed to `hwnd`.
+    Focused(HWND),
+    /// A window was destroyed. Child windows are reported too, not only
+    /// top-level ones.
+    Destroyed(HWND),
+}
+
+// The WinEvent callback has no user context pointer, so the sender is
+// stashed in thread-local storage on the hook thread before the message
+// loop starts. The callback always runs on that same thread.
+thread_local! {
+    static SENDER: std::cell::RefCell<Option<tokio_mpsc::UnboundedSender<ForegroundEvent>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Handle to the running hook. Dropping it tears the hook down cleanly.
+pub struct ForegroundHook {
+    /// Used to ask the message loop to quit.
+    stop: Sender<()>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Install the foreground hook and return it together with a stream of
+/// [`ForegroundEvent`]s. The hook lives on its own thread until the
+/// returned [`ForegroundHook`] is dropped.
+pub fn install() -> (ForegroundHook, UnboundedReceiverStream<ForegroundEvent>) {
+    let (tx, rx) = tokio_mpsc::unbounded_channel();
+    let (stop_tx, stop_rx) = channel::<()>();
+    let (ready_tx, ready_rx) = channel::<u32>();
+
+    let thread = thread::spawn(move || {
+        SENDER.with(|s| *s.borrow_mut() = Some(tx));
+
+        // EVENT_SYSTEM_FOREGROUND for focus changes, EVENT_OBJECT_DESTROY
+        // so we learn when a tracked window is closed.
+        let focus = unsafe {
+            SetWinEventHook(
+                EVENT_SYSTEM_FOREGROUND,
+                EVENT_SYSTEM_FOREGROUND,
+                None,
+                Some(win_event_proc),
+                0,
+                0,
+                WINEVENT_OUTOFCONTEXT,
+            )
+        };
+        let destroy = unsafe {
+            SetWinEventHook(
+                EVENT_OBJECT_DESTROY,
+                EVENT_OBJECT_DESTROY,
+                None,
+                Some(win_event_proc),
+                0,
+                0,
+                WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
+            )
+        };
+
+        // Publish our thread id so the owner can post a quit message.
+        let _ = ready_tx.send(unsafe { GetCurrentThreadId() });
+
+        // Pump messages; the WinEvent callbacks fire from here. WM_QUIT
+        // (posted by the stop watcher below) breaks the loop.
+        let mut msg = MSG::default();
+        while unsafe { GetMessageW(&mut msg, None, 0, 0) }.as_bool() {
+            unsafe {
+                let _ = TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+
+        unsafe {
+            let _ = UnhookWinEvent(focus);
+            let _ = UnhookWinEvent(destroy);
+        }
+        SENDER.with(|s| *s.borrow_mut() = None);
+    });
+
+    // Bridge the blocking stop channel to a posted WM_QUIT on the hook
+    // thread so `GetMessageW` returns and the loop exits.
+    if let Ok(thread_id) = ready_rx.recv() {
+        thread::spawn(move || {
+            let _ = stop_rx.recv();
+            unsafe {
+                let _ = PostThreadMessageW(thread_id, WM_QUIT, WPARAM(0), LPARAM(0));
+            }
+        });
+    }
+
+    (
+        ForegroundHook {
+            stop: stop_tx,
+            thread: Some(thread),
+        },
+        UnboundedReceiverStream::new(rx),
+    )
+}
+
+impl Drop for ForegroundHook {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Runs on the hook thread, inside its message loop: keep it to a channel
+// send and nothing else.
+unsafe extern "system" fn win_event_proc(
+    _hook: HWINEVENTHOOK,
+    event: u32,
+    hwnd: HWND,
+    id_object: i32,
+    _id_child: i32,
+    _thread: u32,
+    _time: u32,
+) {
+    // Only care about window objects, not other accessible objects such as
+    // carets or cursors. Child windows are window objects too and pass.
+    if id_object != OBJID_WINDOW.0 || hwnd.is_invalid() {
+        return;
+    }
+
+    let msg = match event {
+        EVENT_SYSTEM_FOREGROUND => ForegroundEvent::Focused(hwnd),
+        EVENT_OBJECT_DESTROY => ForegroundEvent::Destroyed(hwnd),
+        _ => return,
+    };
+
+    SENDER.with(|s| {
+        if let Some(tx) = s.borrow().as_ref() {
+            let _ = tx.send(msg);
+        }
+    });
+}
@@ -0,0 +1,3 @@
+pub mod foreground;
+pub mod steam;
+pub mod utils;
@@ -0,0 +1,211 @@
+/*
+Steam game identification.
+
+`is_game` used to treat any executable living under a `steamapps`
+directory as a game, which misclassifies tools and mods and gives no
+game identity. This module instead resolves the real Steam library
+layout: it reads the install path from the registry, enumerates every
+library root from `libraryfolders.vdf`, and parses each
+`appmanifest_*.acf` to map an install directory to its AppID and name.
+
+The parsed manifests are cached and re-read only when the `steamapps`
+directory's mtime changes, so freshly installed games are picked up
+without restarting the capture tool.
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use windows::core::PCWSTR;
+use windows::Win32::System::Registry::*;
+
+/// Identity of a known Steam game, keyed by its install directory.
+#[derive(Debug, Clone)]
+pub struct GameInfo {
+    pub app_id: u32,
+    pub name: String,
+}
+
+#[derive(Default)]
+struct Cache {
+    /// Normalized install directory -> game identity.
+    games: HashMap<String, GameInfo>,
+    /// mtime of every scanned `steamapps` root when last scanned, so a
+    /// game installed into any library root invalidates the cache.
+    scanned_at: Vec<(PathBuf, SystemTime)>,
+}
+
+// `HashMap::new` is not a `const fn`, so the cache is built lazily on
+// first access rather than in a `const` static initializer.
+static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<Cache> {
+    CACHE.get_or_init(|| Mutex::new(Cache::default()))
+}
+
+/// Resolve the Steam install path from `HKCU\Software\Valve\Steam\SteamPath`.
+fn steam_path() -> Option<PathBuf> {
+    unsafe {
+        let subkey: Vec<u16> = "Software\\Valve\\Steam\0".encode_utf16().collect();
+        let mut key = HKEY::default();
+        if RegOpenKeyExW(
+            HKEY_CURRENT_USER,
+            PCWSTR(subkey.as_ptr()),
+            Some(0),
+            KEY_READ,
+            &mut key,
+        )
+        .is_err()
+        {
+            return None;
+        }
+
+        let value: Vec<u16> = "SteamPath\0".encode_utf16().collect();
+        let mut buf = [0u16; 1024];
+        let mut len = (buf.len() * 2) as u32;
+        let mut kind = REG_VALUE_TYPE::default();
+        let status = RegQueryValueExW(
+            key,
+            PCWSTR(value.as_ptr()),
+            None,
+            Some(&mut kind),
+            Some(buf.as_mut_ptr() as *mut u8),
+            Some(&mut len),
+        );
+        let _ = RegCloseKey(key);
+
+        if status.is_err() {
+            return None;
+        }
+        let chars = (len as usize / 2).saturating_sub(1);
+        Some(PathBuf::from(String::from_utf16_lossy(&buf[..chars])))
+    }
+}
+
+/// Enumerate every library root declared in `libraryfolders.vdf`. The
+/// primary Steam install directory is always included.
+fn library_roots(steam: &Path) -> Vec<PathBuf> {
+    let mut roots = vec![steam.to_path_buf()];
+    let vdf = steam.join("steamapps").join("libraryfolders.vdf");
+    if let Ok(text) = std::fs::read_to_string(&vdf) {
+        // In modern VDFs the root is carried on a `"path"` key; older
+        // files list the path directly as the value of a numeric key.
+        for (key, value) in vdf_pairs(&text) {
+            if key == "path" || key.parse::<u32>().is_ok() {
+                let p = PathBuf::from(value.replace("\\\\", "\\"));
+                if p.join("steamapps").is_dir() {
+                    roots.push(p);
+                }
+            }
+        }
+    }
+    roots.sort();
+    roots.dedup();
+    roots
+}
+
+/// Parse a single `appmanifest_*.acf` into a `(install_dir, GameInfo)`,
+/// with the install directory in the normalized form used for matching.
+fn parse_manifest(steamapps: &Path, path: &Path) -> Option<(String, GameInfo)> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let pairs: HashMap<String, String> = vdf_pairs(&text).into_iter().collect();
+
+    let app_id = pairs.get("appid")?.parse::<u32>().ok()?;
+    let name = pairs.get("name")?.clone();
+    let install_dir = pairs.get("installdir")?;
+
+    let full = steamapps.join("common").join(install_dir);
+    Some((normalize(&full.to_string_lossy()), GameInfo { app_id, name }))
+}
+
+/// Normalize a path to the case- and separator-insensitive form used for
+/// prefix matching: forward slashes, lowercased, with any `\\?\` DOS
+/// extended-length prefix stripped.
+fn normalize(path: &str) -> String {
+    let slashed = path.replace('\\', "/");
+    let trimmed = slashed.strip_prefix("//?/").unwrap_or(&slashed);
+    trimmed.to_lowercase()
+}
+
+/// (Re)scan all libraries if the cache is cold or any scanned
+/// `steamapps` root changed. Tracking every root — not just the primary
+/// one — means a game installed into a secondary library is picked up
+/// without a restart.
+fn refresh(cache: &mut Cache) {
+    let Some(steam) = steam_path() else { return };
+    let roots = library_roots(&steam);
+
+    let mtimes: Vec<(PathBuf, SystemTime)> = roots
+        .iter()
+        .filter_map(|root| {
+            let apps = root.join("steamapps");
+            let mtime = std::fs::metadata(&apps).and_then(|m| m.modified()).ok()?;
+            Some((apps, mtime))
+        })
+        .collect();
+
+    if !cache.scanned_at.is_empty() && cache.scanned_at == mtimes {
+        return;
+    }
+
+    let mut games = HashMap::new();
+    for (apps, _) in &mtimes {
+        let Ok(entries) = std::fs::read_dir(apps) else { continue };
+        for entry in entries.flatten() {
+            let p = entry.path();
+            let is_manifest = p
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("appmanifest_") && n.ends_with(".acf"));
+            if is_manifest {
+                if let Some((dir, info)) = parse_manifest(apps, &p) {
+                    games.insert(dir, info);
+                }
+            }
+        }
+    }
+
+    cache.games = games;
+    cache.scanned_at = mtimes;
+}
+
+/// Match a process image path against the known install directories and
+/// return the owning game, if any. Both sides are reduced to the same
+/// normalized form first (see [`normalize`]).
+pub fn identify(image_path: &str) -> Option<GameInfo> {
+    let needle = normalize(image_path);
+
+    let mut cache = cache().lock().ok()?;
+    refresh(&mut cache);
+
+    cache
+        .games
+        .iter()
+        .find(|(dir, _)| {
+            // Match whole path components only, so `common/portal` does
+            // not claim `common/portal 2/...`.
+            needle
+                .strip_prefix(dir.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .map(|(_, info)| info.clone())
+}
+
+/// Minimal VDF/ACF tokenizer: yields every `"key" "value"` pair,
+/// ignoring nesting braces. Sufficient for the flat lookups we need.
+fn vdf_pairs(text: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    for line in text.lines() {
+        let mut tokens = line.split('"').filter(|t| !t.trim().is_empty());
+        if let (Some(key), Some(value)) = (tokens.next(), tokens.next()) {
+            // A value-bearing line has exactly key + value; lines that
+            // open a nested block ("key" {) leave `value` as the brace.
+            if value.trim() != "{" {
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+    pairs
+}
@@ -1,12 +1,14 @@
 use std::ptr;
 
+use windows::core::PWSTR;
 use windows::{
     Win32::Foundation::*,
     Win32::UI::WindowsAndMessaging::*,
     Win32::System::Threading::*,
-    Win32::System::ProcessStatus::*
 };
 
+use crate::steam::GameInfo;
+
 pub fn get_foreground_window_hwnd() -> Option<HWND> {
     unsafe {
         let hwnd = GetForegroundWindow();
@@ -21,19 +23,28 @@ pub fn get_file_path_hwnd(hwnd: HWND) -> Option<String> {
         GetWindowThreadProcessId(hwnd, Some(&mut pid));
         if pid == 0 { return None; }
 
-        let p_handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)
-            .expect("Failed to open process");
+        let p_handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
 
-        // Buffer to store the file path
+        // Ask for the Win32 DOS path (`C:\...`) rather than a device path
+        // (`\Device\HarddiskVolume2\...`) so it can be prefix-matched
+        // against Steam's install directories, which are DOS paths too.
         let mut buffer: [u16; MAX_PATH as usize] = [0; MAX_PATH as usize];
-        let len = GetProcessImageFileNameW(p_handle, &mut buffer) as usize;
+        let mut len = buffer.len() as u32;
+        let ok = QueryFullProcessImageNameW(
+            p_handle,
+            PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut len,
+        )
+        .is_ok();
 
         // Close the process handle
-        let _ = CloseHandle(p_handle)
-            .expect("Failed to close process");
+        let _ = CloseHandle(p_handle);
+
+        if !ok { return None; }
 
         // Convert the buffer to a string
-        let file_path = String::from_utf16_lossy(&buffer[..len]);
+        let file_path = String::from_utf16_lossy(&buffer[..len as usize]);
 
         // process the file path
         let file_path = file_path.replace("\\", "/");
@@ -42,23 +53,81 @@ pub fn get_file_path_hwnd(hwnd: HWND) -> Option<String> {
     }
 }
 
-pub fn is_game(hwnd: Option<HWND>) -> bool {
+// Identify the game owning the given window, if any. Resolves the
+// process image path and matches it against the known Steam install
+// directories (see the `steam` module), returning the AppID and display
+// name rather than a bare boolean.
+pub fn is_game(hwnd: Option<HWND>) -> Option<GameInfo> {
 
     let handle = match hwnd {
         Some(hwnd) => hwnd,
-        None => return false 
+        None => return None
     };
 
     let file_path = match get_file_path_hwnd(handle){
         Some(fp) => fp,
-        None => return false 
+        None => return None
     };
 
-    if file_path.contains("steamapps") {
-        return true;
+    crate::steam::identify(&file_path)
+}
+
+// Stable identity for a specific process instance. Windows recycles both
+// PIDs and window handles, so a PID on its own is not enough to tell
+// whether the process we originally saw is still the one running. Pairing
+// the PID with its creation time pins us to one concrete instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessIdentity {
+    pub pid: u32,
+    pub creation_time: FILETIME,
+}
+
+// Read the creation time of `pid`, opening the process with the minimum
+// access needed. Returns None if the process can no longer be opened.
+fn process_creation_time(pid: u32) -> Option<FILETIME> {
+    unsafe {
+        let p_handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+
+        let mut creation = FILETIME::default();
+        let mut exit = FILETIME::default();
+        let mut kernel = FILETIME::default();
+        let mut user = FILETIME::default();
+        let ok = GetProcessTimes(p_handle, &mut creation, &mut exit, &mut kernel, &mut user)
+            .is_ok();
+        let _ = CloseHandle(p_handle);
+
+        if ok { Some(creation) } else { None }
     }
+}
 
-   false 
+// Capture the stable identity of the process that owns `hwnd`, recording
+// both its PID and creation time. Call this the moment a game is first
+// detected.
+pub fn capture_identity(hwnd: HWND) -> Option<ProcessIdentity> {
+    unsafe {
+        let mut pid = 0;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        if pid == 0 { return None; }
+
+        let creation_time = process_creation_time(pid)?;
+        Some(ProcessIdentity { pid, creation_time })
+    }
+}
+
+// Check whether `hwnd` still belongs to the exact process captured in
+// `identity`. A recycled PID owned by a different process reports a
+// different creation time and is correctly treated as dead.
+pub fn is_same_process(identity: ProcessIdentity, hwnd: HWND) -> bool {
+    unsafe {
+        let mut pid = 0;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        if pid == 0 || pid != identity.pid { return false; }
+
+        match process_creation_time(pid) {
+            Some(creation) => creation == identity.creation_time,
+            None => false,
+        }
+    }
 }
 
 // Check if the window is alive